@@ -2,49 +2,82 @@
 
 use std::cell::RefCell;
 use std::collections::{btree_map, BTreeMap};
+use std::fs::{File, OpenOptions};
 use std::ops::Bound::{Excluded, Included};
 use std::path::Path;
 
+use memmap2::MmapOptions;
+
 use super::{
-    BlockStateRead, BlockStateWrite, DBIter, DBWriteBatch, Error, Result, DB,
+    BlockStateRead, BlockStateWrite, DBIter, DBWriteBatch, Error,
+    MembershipProof, MerkleTree, MerkleTreeStoresRead, Result, DB,
 };
 use crate::ledger::storage::types::{self, KVBytes, PrefixIterator};
 use crate::types::storage::{BlockHeight, Key, KeySeg, KEY_SEGMENT_SEPARATOR};
 use crate::types::time::DateTimeUtc;
 
-/// An in-memory DB for testing.
+/// An in-memory DB for testing, optionally backed by a memory-mapped file.
+///
+/// [`DB::open`] always returns a purely ephemeral `MockDB`, ignoring its
+/// path argument - most callers pass a placeholder or a directory path (as
+/// the RocksDB backend expects), and neither should panic or leak state
+/// into a durable file. Tests that specifically want to assert
+/// crash-recovery behaviour should opt in explicitly via
+/// [`MockDB::open_durable`], which snapshots the current map to a
+/// memory-mapped file on `flush` (and on drop), and reloads it on the next
+/// `open_durable` of the same path. This lets such tests kill and reopen a
+/// `MockDB` to assert that `read_last_block` recovers the last committed
+/// state, without pulling in the RocksDB backend.
 #[derive(Debug, Default)]
-pub struct MockDB(
+pub struct MockDB {
     // The state is wrapped in `RefCell` to allow modifying it directly from
-    // batch write method (which requires immutable self ref).
-    RefCell<BTreeMap<String, Vec<u8>>>,
-);
+    // `&self`, though the batch write methods (which take an immutable self
+    // ref) only read it and queue their writes on the batch. This is the
+    // working set; `file`, when present, is the durable image.
+    data: RefCell<BTreeMap<String, Vec<u8>>>,
+    file: Option<File>,
+}
 
 // The `MockDB` is not `Sync`, but we're sharing it across threads for reading
 // only (for parallelized VP runs). In a different context, this may not be
 // safe.
 unsafe impl Sync for MockDB {}
 
-/// An in-memory write batch is not needed as it just updates values in memory.
-/// It's here to satisfy the storage interface.
+/// An in-memory write batch that buffers puts and deletes until
+/// [`DB::exec_batch`] applies them in one shot, so that nothing written to
+/// the batch is visible until it's committed. This mirrors the
+/// atomic write-batch semantics of the RocksDB backend.
 #[derive(Debug, Default)]
-pub struct MockDBWriteBatch;
+pub struct MockDBWriteBatch(Vec<BatchOp>);
+
+#[derive(Debug)]
+enum BatchOp {
+    Put { key: String, value: Vec<u8> },
+    Delete { key: String },
+}
 
 impl DB for MockDB {
     type WriteBatch = MockDBWriteBatch;
 
-    fn open(_db_path: impl AsRef<Path>) -> Self {
+    fn open(db_path: impl AsRef<Path>) -> Self {
+        // Durable, file-backed mode is an explicit opt-in - see
+        // `MockDB::open_durable` - so that ordinary callers of this trait
+        // method, which typically pass a placeholder or a RocksDB-style
+        // directory path, always get a safe, ephemeral `MockDB` instead of
+        // an `EISDIR` panic or state leaking across unrelated test runs
+        // that happen to reuse the same path.
+        let _ = db_path;
         Self::default()
     }
 
     fn flush(&self) -> Result<()> {
-        Ok(())
+        self.flush_to_file()
     }
 
     fn read_last_block(&mut self) -> Result<Option<BlockStateRead>> {
         // Block height
         let height: BlockHeight;
-        match self.0.borrow().get("height") {
+        match self.data.borrow().get("height") {
             Some(bytes) => {
                 height = types::decode(bytes).map_err(Error::CodingError)?;
             }
@@ -53,14 +86,14 @@ impl DB for MockDB {
 
         // Epoch start height and time
         let next_epoch_min_start_height: BlockHeight =
-            match self.0.borrow().get("next_epoch_min_start_height") {
+            match self.data.borrow().get("next_epoch_min_start_height") {
                 Some(bytes) => {
                     types::decode(bytes).map_err(Error::CodingError)?
                 }
                 None => return Ok(None),
             };
         let next_epoch_min_start_time: DateTimeUtc =
-            match self.0.borrow().get("next_epoch_min_start_time") {
+            match self.data.borrow().get("next_epoch_min_start_time") {
                 Some(bytes) => {
                     types::decode(bytes).map_err(Error::CodingError)?
                 }
@@ -77,7 +110,7 @@ impl DB for MockDB {
         let mut pred_epochs = None;
         let mut address_gen = None;
         for (path, bytes) in self
-            .0
+            .data
             .borrow()
             .range((Included(prefix), Excluded(upper_prefix)))
         {
@@ -168,11 +201,11 @@ impl DB for MockDB {
         }: BlockStateWrite = state;
 
         // Epoch start height and time
-        self.0.borrow_mut().insert(
+        self.data.borrow_mut().insert(
             "next_epoch_min_start_height".into(),
             types::encode(&next_epoch_min_start_height),
         );
-        self.0.borrow_mut().insert(
+        self.data.borrow_mut().insert(
             "next_epoch_min_start_time".into(),
             types::encode(&next_epoch_min_start_time),
         );
@@ -188,7 +221,7 @@ impl DB for MockDB {
                 let key = prefix_key
                     .push(&"root".to_owned())
                     .map_err(Error::KeyError)?;
-                self.0
+                self.data
                     .borrow_mut()
                     .insert(key.to_string(), types::encode(&root));
             }
@@ -197,7 +230,7 @@ impl DB for MockDB {
                 let key = prefix_key
                     .push(&"store".to_owned())
                     .map_err(Error::KeyError)?;
-                self.0
+                self.data
                     .borrow_mut()
                     .insert(key.to_string(), types::encode(&store));
             }
@@ -207,7 +240,7 @@ impl DB for MockDB {
             let key = prefix_key
                 .push(&"hash".to_owned())
                 .map_err(Error::KeyError)?;
-            self.0
+            self.data
                 .borrow_mut()
                 .insert(key.to_string(), types::encode(&hash));
         }
@@ -216,7 +249,7 @@ impl DB for MockDB {
             let key = prefix_key
                 .push(&"epoch".to_owned())
                 .map_err(Error::KeyError)?;
-            self.0
+            self.data
                 .borrow_mut()
                 .insert(key.to_string(), types::encode(&epoch));
         }
@@ -225,7 +258,7 @@ impl DB for MockDB {
             let key = prefix_key
                 .push(&"pred_epochs".to_owned())
                 .map_err(Error::KeyError)?;
-            self.0
+            self.data
                 .borrow_mut()
                 .insert(key.to_string(), types::encode(&pred_epochs));
         }
@@ -235,11 +268,11 @@ impl DB for MockDB {
                 .push(&"address_gen".to_owned())
                 .map_err(Error::KeyError)?;
             let value = &address_gen;
-            self.0
+            self.data
                 .borrow_mut()
                 .insert(key.to_string(), types::encode(value));
         }
-        self.0
+        self.data
             .borrow_mut()
             .insert("height".to_owned(), types::encode(&height));
         Ok(())
@@ -249,103 +282,350 @@ impl DB for MockDB {
         let key = Key::parse(&"subspace".to_owned())
             .map_err(Error::KeyError)?
             .join(key);
-        Ok(self.0.borrow().get(&key.to_string()).cloned())
+        Ok(self.data.borrow().get(&key.to_string()).cloned())
+    }
+
+    fn read_subspace_val_at_height(
+        &self,
+        key: &Key,
+        height: BlockHeight,
+    ) -> Result<Option<Vec<u8>>> {
+        // Diffs are keyed key-major (`diffs/<key>/<height>`), so every
+        // version of `key` `<= height` lies in the range bounded below by
+        // this key's diffs subtree and above by `height` itself. Diffs of
+        // descendant keys (e.g. `diffs/<key>/0/<height>`) can sort inside
+        // that range too, so the greatest recorded version is the last
+        // entry in it whose remainder after the lower bound is a single
+        // (height) segment - a single `BTreeMap` range lookup, not a
+        // per-height scan.
+        let lower = diffs_lower_bound(key)?;
+        let lower_len = lower.len();
+        let upper = diff_key(height, key)?.to_string();
+        let data = self.data.borrow();
+        match data
+            .range((Included(lower), Included(upper)))
+            .rev()
+            .find(|(path, _)| {
+                !path[lower_len..].contains(KEY_SEGMENT_SEPARATOR)
+            }) {
+            Some((_, bytes)) => {
+                let value: Option<Vec<u8>> =
+                    types::decode(bytes).map_err(Error::CodingError)?;
+                Ok(value)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn read_subspace_val_with_proof(
+        &self,
+        key: &Key,
+        height: BlockHeight,
+    ) -> Result<(Option<Vec<u8>>, MembershipProof)> {
+        let value = self.read_subspace_val_at_height(key, height)?;
+
+        // Load the Merkle tree store recorded for `height`, the same
+        // `tree/store` entry `read_last_block` reads for the latest height.
+        let store_key = Key::from(height.to_db_key())
+            .push(&"tree".to_owned())
+            .map_err(Error::KeyError)?
+            .push(&"store".to_owned())
+            .map_err(Error::KeyError)?;
+        let store_bytes = self
+            .data
+            .borrow()
+            .get(&store_key.to_string())
+            .cloned()
+            .ok_or_else(|| Error::Temporary {
+                error: format!(
+                    "No Merkle tree store recorded for height {}",
+                    height.raw()
+                ),
+            })?;
+        let store: MerkleTreeStoresRead =
+            types::decode(&store_bytes).map_err(Error::CodingError)?;
+        let tree = MerkleTree::new(store).map_err(|error| Error::Temporary {
+            error: format!("Couldn't rebuild the Merkle tree: {}", error),
+        })?;
+        // A caller holding only the block root can verify either that
+        // `key` is present with this value (inclusion), or that it's
+        // absent (non-membership) - both are proven against `tree/root`.
+        let proof = match &value {
+            Some(value) => {
+                tree.get_sub_tree_existence_proof(key, value.clone())?
+            }
+            None => tree.get_sub_tree_non_existence_proof(key)?,
+        };
+        Ok((value, proof))
     }
 
     fn write_subspace_val(
         &mut self,
-        _height: BlockHeight,
+        height: BlockHeight,
         key: &Key,
         value: impl AsRef<[u8]>,
     ) -> Result<i64> {
         let value = value.as_ref();
-        let key = Key::parse(&"subspace".to_owned())
+        let subspace_key = Key::parse(&"subspace".to_owned())
             .map_err(Error::KeyError)?
             .join(key);
         let current_len = value.len() as i64;
-        Ok(
-            match self
-                .0
-                .borrow_mut()
-                .insert(key.to_string(), value.to_owned())
-            {
-                Some(prev_value) => current_len - prev_value.len() as i64,
-                None => current_len,
-            },
-        )
+        let diff = match self
+            .data
+            .borrow_mut()
+            .insert(subspace_key.to_string(), value.to_owned())
+        {
+            Some(prev_value) => current_len - prev_value.len() as i64,
+            None => current_len,
+        };
+        let diff_key = diff_key(height, key)?;
+        self.data.borrow_mut().insert(
+            diff_key.to_string(),
+            types::encode(&Some(value.to_owned())),
+        );
+        Ok(diff)
     }
 
     fn delete_subspace_val(
         &mut self,
-        _height: BlockHeight,
+        height: BlockHeight,
         key: &Key,
     ) -> Result<i64> {
-        let key = Key::parse(&"subspace".to_owned())
+        let subspace_key = Key::parse(&"subspace".to_owned())
             .map_err(Error::KeyError)?
             .join(key);
-        Ok(match self.0.borrow_mut().remove(&key.to_string()) {
+        let len = match self.data.borrow_mut().remove(&subspace_key.to_string()) {
             Some(value) => value.len() as i64,
             None => 0,
-        })
+        };
+        let diff_key = diff_key(height, key)?;
+        self.data.borrow_mut().insert(
+            diff_key.to_string(),
+            types::encode(&(None::<Vec<u8>>)),
+        );
+        Ok(len)
+    }
+
+    fn delete_subspace_prefix(
+        &mut self,
+        height: BlockHeight,
+        prefix: &Key,
+    ) -> Result<i64> {
+        let db_prefix = "subspace/".to_owned();
+        let full_prefix = subspace_prefix(prefix);
+        let upper_prefix = prefix_upper_bound(&full_prefix);
+        let matching: Vec<(String, Vec<u8>)> = self
+            .data
+            .borrow()
+            .range((Included(full_prefix), Excluded(upper_prefix)))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        let mut total_len = 0_i64;
+        for (raw_key, value) in matching {
+            self.data.borrow_mut().remove(&raw_key);
+            total_len += value.len() as i64;
+            if let Some(sub_key) = raw_key.strip_prefix(&db_prefix) {
+                let key = Key::parse(sub_key).map_err(Error::KeyError)?;
+                let diff_key = diff_key(height, &key)?;
+                self.data.borrow_mut().insert(
+                    diff_key.to_string(),
+                    types::encode(&(None::<Vec<u8>>)),
+                );
+            }
+        }
+        Ok(total_len)
     }
 
     fn batch() -> Self::WriteBatch {
-        MockDBWriteBatch
+        MockDBWriteBatch(Vec::new())
     }
 
-    fn exec_batch(&mut self, _batch: Self::WriteBatch) -> Result<()> {
-        // Nothing to do - in MockDB, batch writes are committed directly from
-        // `batch_write_subspace_val` and `batch_delete_subspace_val`.
+    fn exec_batch(&mut self, batch: Self::WriteBatch) -> Result<()> {
+        for op in batch.0 {
+            match op {
+                BatchOp::Put { key, value } => {
+                    self.data.borrow_mut().insert(key, value);
+                }
+                BatchOp::Delete { key } => {
+                    self.data.borrow_mut().remove(&key);
+                }
+            }
+        }
         Ok(())
     }
 
     fn batch_write_subspace_val(
         &self,
-        _batch: &mut Self::WriteBatch,
-        _height: BlockHeight,
+        batch: &mut Self::WriteBatch,
+        height: BlockHeight,
         key: &Key,
         value: impl AsRef<[u8]>,
     ) -> Result<i64> {
         let value = value.as_ref();
-        let key = Key::parse(&"subspace".to_owned())
+        let subspace_key = Key::parse(&"subspace".to_owned())
             .map_err(Error::KeyError)?
             .join(key);
         let current_len = value.len() as i64;
-        Ok(
-            match self
-                .0
-                .borrow_mut()
-                .insert(key.to_string(), value.to_owned())
-            {
-                Some(prev_value) => current_len - prev_value.len() as i64,
-                None => current_len,
-            },
-        )
+        let diff = match self.data.borrow().get(&subspace_key.to_string()) {
+            Some(prev_value) => current_len - prev_value.len() as i64,
+            None => current_len,
+        };
+        batch.0.push(BatchOp::Put {
+            key: subspace_key.to_string(),
+            value: value.to_owned(),
+        });
+        let diff_key = diff_key(height, key)?;
+        batch.0.push(BatchOp::Put {
+            key: diff_key.to_string(),
+            value: types::encode(&Some(value.to_owned())),
+        });
+        Ok(diff)
     }
 
     fn batch_delete_subspace_val(
         &self,
-        _batch: &mut Self::WriteBatch,
-        _height: BlockHeight,
+        batch: &mut Self::WriteBatch,
+        height: BlockHeight,
         key: &Key,
     ) -> Result<i64> {
-        let key = Key::parse(&"subspace".to_owned())
+        let subspace_key = Key::parse(&"subspace".to_owned())
             .map_err(Error::KeyError)?
             .join(key);
-        Ok(match self.0.borrow_mut().remove(&key.to_string()) {
+        let len = match self.data.borrow().get(&subspace_key.to_string()) {
             Some(value) => value.len() as i64,
             None => 0,
+        };
+        batch.0.push(BatchOp::Delete {
+            key: subspace_key.to_string(),
+        });
+        let diff_key = diff_key(height, key)?;
+        batch.0.push(BatchOp::Put {
+            key: diff_key.to_string(),
+            value: types::encode(&(None::<Vec<u8>>)),
+        });
+        Ok(len)
+    }
+
+    fn batch_delete_subspace_prefix(
+        &self,
+        batch: &mut Self::WriteBatch,
+        height: BlockHeight,
+        prefix: &Key,
+    ) -> Result<i64> {
+        let db_prefix = "subspace/".to_owned();
+        let full_prefix = subspace_prefix(prefix);
+        let upper_prefix = prefix_upper_bound(&full_prefix);
+        let matching: Vec<(String, Vec<u8>)> = self
+            .data
+            .borrow()
+            .range((Included(full_prefix), Excluded(upper_prefix)))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        let mut total_len = 0_i64;
+        for (raw_key, value) in matching {
+            batch.0.push(BatchOp::Delete {
+                key: raw_key.clone(),
+            });
+            total_len += value.len() as i64;
+            if let Some(sub_key) = raw_key.strip_prefix(&db_prefix) {
+                let key = Key::parse(sub_key).map_err(Error::KeyError)?;
+                let diff_key = diff_key(height, &key)?;
+                batch.0.push(BatchOp::Put {
+                    key: diff_key.to_string(),
+                    value: types::encode(&(None::<Vec<u8>>)),
+                });
+            }
+        }
+        Ok(total_len)
+    }
+}
+
+impl MockDB {
+    /// Open a `MockDB` backed by a memory-mapped file at `path`. Unlike
+    /// [`DB::open`], this durably snapshots the current map to `path` on
+    /// `flush` (and on drop), and reloads it here on a later
+    /// `open_durable` of the same path - an explicit opt-in for tests that
+    /// want to assert crash-recovery behaviour (e.g. that `read_last_block`
+    /// recovers the last committed state across a flush/reopen cycle),
+    /// without pulling in the RocksDB backend.
+    pub fn open_durable(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .open(path)
+            .expect("Couldn't open the MockDB's backing file");
+        let is_empty = file
+            .metadata()
+            .expect("Couldn't read the MockDB's backing file metadata")
+            .len()
+            == 0;
+        let data = if is_empty {
+            BTreeMap::new()
+        } else {
+            let mmap = unsafe {
+                MmapOptions::new()
+                    .map(&file)
+                    .expect("Couldn't mmap the MockDB's backing file")
+            };
+            types::decode(&mmap[..])
+                .expect("Couldn't decode the MockDB's backing file snapshot")
+        };
+        Self {
+            data: RefCell::new(data),
+            file: Some(file),
+        }
+    }
+
+    /// Write the current map back to the memory-mapped backing file and
+    /// `msync` it, if one was given to `open_durable`. This is the durable
+    /// image that a fresh `open_durable` of the same path reloads from.
+    fn flush_to_file(&self) -> Result<()> {
+        let file = match &self.file {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+        let bytes = types::encode(&*self.data.borrow());
+        file.set_len(bytes.len() as u64).map_err(|e| {
+            Error::Temporary {
+                error: format!(
+                    "Couldn't resize the MockDB's backing file: {}",
+                    e
+                ),
+            }
+        })?;
+        let mut mmap = unsafe {
+            MmapOptions::new().map_mut(file).map_err(|e| {
+                Error::Temporary {
+                    error: format!(
+                        "Couldn't mmap the MockDB's backing file: {}",
+                        e
+                    ),
+                }
+            })?
+        };
+        mmap[..].copy_from_slice(&bytes);
+        mmap.flush().map_err(|e| Error::Temporary {
+            error: format!("Couldn't msync the MockDB's backing file: {}", e),
         })
     }
 }
 
+impl Drop for MockDB {
+    fn drop(&mut self) {
+        // Best-effort: a failure to flush on drop shouldn't panic.
+        let _ = self.flush_to_file();
+    }
+}
+
 impl<'iter> DBIter<'iter> for MockDB {
     type PrefixIter = MockPrefixIterator;
 
     fn iter_prefix(&'iter self, prefix: &Key) -> MockPrefixIterator {
         let db_prefix = "subspace/".to_owned();
-        let prefix = format!("{}{}", db_prefix, prefix);
-        let iter = self.0.borrow().clone().into_iter();
+        let prefix = subspace_prefix(prefix);
+        let iter = self.data.borrow().clone().into_iter();
         MockPrefixIterator::new(MockIterator { prefix, iter }, db_prefix)
     }
 }
@@ -365,6 +645,8 @@ impl Iterator for MockIterator {
     type Item = KVBytes;
 
     fn next(&mut self) -> Option<Self::Item> {
+        // `self.prefix` always ends on a `KEY_SEGMENT_SEPARATOR` boundary
+        // (see `subspace_prefix` for why that matters here).
         for (key, val) in &mut self.iter {
             if key.starts_with(&self.prefix) {
                 return Some((
@@ -400,18 +682,23 @@ impl Iterator for PrefixIterator<MockIterator> {
 }
 
 impl DBWriteBatch for MockDBWriteBatch {
-    fn put<K, V>(&mut self, _key: K, _value: V)
+    fn put<K, V>(&mut self, key: K, value: V)
     where
         K: AsRef<[u8]>,
         V: AsRef<[u8]>,
     {
-        // Nothing to do - in MockDB, batch writes are committed directly from
-        // `batch_write_subspace_val` and `batch_delete_subspace_val`.
+        let key = String::from_utf8(key.as_ref().to_vec())
+            .expect("Cannot convert from bytes to key string");
+        self.0.push(BatchOp::Put {
+            key,
+            value: value.as_ref().to_owned(),
+        });
     }
 
-    fn delete<K: AsRef<[u8]>>(&mut self, _key: K) {
-        // Nothing to do - in MockDB, batch writes are committed directly from
-        // `batch_write_subspace_val` and `batch_delete_subspace_val`.
+    fn delete<K: AsRef<[u8]>>(&mut self, key: K) {
+        let key = String::from_utf8(key.as_ref().to_vec())
+            .expect("Cannot convert from bytes to key string");
+        self.0.push(BatchOp::Delete { key });
     }
 }
 
@@ -420,3 +707,300 @@ fn unknown_key_error(key: &str) -> Result<()> {
         key: key.to_owned(),
     })
 }
+
+/// Build the `subspace/` key prefix used for range scans over `prefix`,
+/// terminated by a `KEY_SEGMENT_SEPARATOR` so that a scan only matches whole
+/// descendant segments of `prefix` (e.g. `subspace/abc/*`), not unrelated
+/// keys that merely share a string prefix (e.g. `subspace/abcd`).
+fn subspace_prefix(prefix: &Key) -> String {
+    let db_prefix = "subspace/".to_owned();
+    if prefix.is_empty() {
+        db_prefix
+    } else {
+        format!("{}{}{}", db_prefix, prefix, KEY_SEGMENT_SEPARATOR)
+    }
+}
+
+/// Compute an exclusive upper bound for a range scan over every DB entry
+/// whose key starts with `prefix`, by incrementing the last character of
+/// `prefix` (where `read_last_block` builds its `upper_prefix` from
+/// `next_height` instead): the subtree rooted at `prefix` sorts between
+/// `prefix` and this bound, and nothing else does.
+fn prefix_upper_bound(prefix: &str) -> String {
+    let mut upper = prefix.to_owned();
+    if let Some(last) = upper.pop() {
+        upper.push(
+            char::from_u32(last as u32 + 1)
+                .expect("key strings shouldn't contain the maximum char value"),
+        );
+    }
+    upper
+}
+
+/// Number of decimal digits in `u64::MAX`, used to zero-pad the height
+/// segment of a diff key so that string-lexicographic order agrees with
+/// numeric height order (see `diff_key`).
+const HEIGHT_DIGITS: usize = 20;
+
+/// Build the diff key that records the version of `key` written (or
+/// deleted) at `height`, key-major: `diffs/<key>/<height>`. The value
+/// stored there is a `types`-encoded `Option<Vec<u8>>` - `Some(value)` for
+/// a write, `None` as a tombstone for a delete - so that looking up the
+/// greatest recorded version `<= height` for a given key is a single
+/// `BTreeMap` range query (see `diffs_lower_bound` and
+/// `read_subspace_val_at_height`) instead of a per-height scan.
+///
+/// The height is zero-padded rather than pushed via `BlockHeight`'s own
+/// `KeySeg` encoding (which, per the plain `height.raw()` formatting
+/// `read_last_block` uses elsewhere in this file, is unpadded decimal):
+/// an unpadded `"5"` sorts after `"10"` as a string, which would make the
+/// range query above resolve to the wrong predecessor version as soon as
+/// a key's diffs span a digit-width boundary.
+fn diff_key(height: BlockHeight, key: &Key) -> Result<Key> {
+    let height_segment = format!("{:0width$}", height.raw(), width = HEIGHT_DIGITS);
+    Key::parse(&"diffs".to_owned())
+        .map_err(Error::KeyError)?
+        .join(key)
+        .push(&height_segment)
+        .map_err(Error::KeyError)
+}
+
+/// Compute the inclusive lower bound for a range scan over every diff
+/// entry recorded for `key`, i.e. `diffs/<key>/`, terminated by a
+/// `KEY_SEGMENT_SEPARATOR` for the same reason as `subspace_prefix`: so the
+/// scan can't bleed into a different key's diffs that merely share a
+/// string prefix (e.g. `diffs/abcd/..`).
+fn diffs_lower_bound(key: &Key) -> Result<String> {
+    let key_prefix = Key::parse(&"diffs".to_owned())
+        .map_err(Error::KeyError)?
+        .join(key);
+    Ok(format!("{}{}", key_prefix, KEY_SEGMENT_SEPARATOR))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(s: &str) -> Key {
+        Key::parse(&s.to_owned()).unwrap()
+    }
+
+    /// chunk0-1: a value written at one height must still be readable at
+    /// that height once later writes and deletes have moved `key` on.
+    #[test]
+    fn test_read_subspace_val_at_height() {
+        let mut db = MockDB::default();
+        let key = key("k");
+
+        db.write_subspace_val(BlockHeight(1), &key, b"v1").unwrap();
+        db.write_subspace_val(BlockHeight(3), &key, b"v3").unwrap();
+        db.delete_subspace_val(BlockHeight(5), &key).unwrap();
+
+        assert_eq!(
+            db.read_subspace_val_at_height(&key, BlockHeight(0))
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            db.read_subspace_val_at_height(&key, BlockHeight(1))
+                .unwrap(),
+            Some(b"v1".to_vec())
+        );
+        assert_eq!(
+            db.read_subspace_val_at_height(&key, BlockHeight(2))
+                .unwrap(),
+            Some(b"v1".to_vec())
+        );
+        assert_eq!(
+            db.read_subspace_val_at_height(&key, BlockHeight(3))
+                .unwrap(),
+            Some(b"v3".to_vec())
+        );
+        assert_eq!(
+            db.read_subspace_val_at_height(&key, BlockHeight(5))
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            db.read_subspace_val_at_height(&key, BlockHeight(10))
+                .unwrap(),
+            None
+        );
+        // The latest value reflects only the delete, not the historical
+        // reads above.
+        assert_eq!(db.read_subspace_val(&key).unwrap(), None);
+    }
+
+    /// chunk0-1: a naive string-ordered range lookup over unpadded height
+    /// segments would resolve the "greatest version <= height" query wrong
+    /// as soon as a key's diffs span a digit-width boundary (`"9" < "11"`
+    /// numerically, but `"11" < "9"` lexicographically). Regression test
+    /// for that case: write at height 9, delete at height 11, and query at
+    /// the in-between height 10.
+    #[test]
+    fn test_read_subspace_val_at_height_across_digit_width_boundary() {
+        let mut db = MockDB::default();
+        let key = key("k");
+
+        db.write_subspace_val(BlockHeight(9), &key, b"v9").unwrap();
+        db.delete_subspace_val(BlockHeight(11), &key).unwrap();
+
+        assert_eq!(
+            db.read_subspace_val_at_height(&key, BlockHeight(10))
+                .unwrap(),
+            Some(b"v9".to_vec())
+        );
+        assert_eq!(
+            db.read_subspace_val_at_height(&key, BlockHeight(11))
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            db.read_subspace_val_at_height(&key, BlockHeight(100))
+                .unwrap(),
+            None
+        );
+    }
+
+    /// chunk0-2: writes queued on a batch must not be visible until
+    /// `exec_batch` runs, and dropping the batch without executing it
+    /// must leave the DB untouched.
+    #[test]
+    fn test_batch_commit_or_discard() {
+        let mut db = MockDB::default();
+        let key = key("k");
+
+        let mut batch = MockDB::batch();
+        db.batch_write_subspace_val(&mut batch, BlockHeight(1), &key, b"v")
+            .unwrap();
+        assert_eq!(db.read_subspace_val(&key).unwrap(), None);
+        drop(batch);
+        assert_eq!(db.read_subspace_val(&key).unwrap(), None);
+
+        let mut batch = MockDB::batch();
+        db.batch_write_subspace_val(&mut batch, BlockHeight(1), &key, b"v")
+            .unwrap();
+        db.exec_batch(batch).unwrap();
+        assert_eq!(db.read_subspace_val(&key).unwrap(), Some(b"v".to_vec()));
+    }
+
+    /// chunk0-5: a `MockDB` opened durably at a path must recover its last
+    /// committed block state after being flushed, dropped, and reopened.
+    #[test]
+    fn test_flush_and_reopen_recovers_last_block() {
+        let path = std::env::temp_dir()
+            .join(format!("mockdb_test_{}.bin", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        {
+            let mut db = MockDB::open_durable(&path);
+            db.write_block(BlockStateWrite {
+                root: Default::default(),
+                store: Default::default(),
+                hash: Default::default(),
+                height: BlockHeight(7),
+                epoch: Default::default(),
+                pred_epochs: Default::default(),
+                next_epoch_min_start_height: BlockHeight(100),
+                next_epoch_min_start_time: DateTimeUtc::now(),
+                address_gen: Default::default(),
+            })
+            .unwrap();
+            db.flush().unwrap();
+        }
+
+        let mut reopened = MockDB::open_durable(&path);
+        let state = reopened.read_last_block().unwrap().unwrap();
+        assert_eq!(state.height, BlockHeight(7));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// chunk0-6: reads with proof must return a proof both when the key is
+    /// present (inclusion) and when it's absent (non-membership), not just
+    /// the membership half. The value is inserted into a real `MerkleTree`
+    /// (not an empty, default one) so the proof is checked against a tree
+    /// that actually contains the data.
+    #[test]
+    fn test_read_subspace_val_with_proof() {
+        let mut db = MockDB::default();
+        let key = key("k");
+        let value = b"v1".to_vec();
+        db.write_subspace_val(BlockHeight(1), &key, &value).unwrap();
+
+        let mut tree = MerkleTree::default();
+        tree.update(&key, &value).unwrap();
+
+        db.write_block(BlockStateWrite {
+            root: types::encode(&tree.root()),
+            store: types::encode(&tree.stores()),
+            hash: Default::default(),
+            height: BlockHeight(1),
+            epoch: Default::default(),
+            pred_epochs: Default::default(),
+            next_epoch_min_start_height: BlockHeight(100),
+            next_epoch_min_start_time: DateTimeUtc::now(),
+            address_gen: Default::default(),
+        })
+        .unwrap();
+
+        let (value, _proof) = db
+            .read_subspace_val_with_proof(&key, BlockHeight(1))
+            .unwrap();
+        assert_eq!(value, Some(b"v1".to_vec()));
+
+        let (value, _non_membership_proof) = db
+            .read_subspace_val_with_proof(
+                &Key::parse(&"other".to_owned()).unwrap(),
+                BlockHeight(1),
+            )
+            .unwrap();
+        assert_eq!(value, None);
+    }
+
+    /// chunk0-3: deleting a prefix must only remove keys in that prefix's
+    /// subtree, not lexicographic neighbors (see `subspace_prefix`).
+    #[test]
+    fn test_delete_subspace_prefix_respects_segment_boundary() {
+        let mut db = MockDB::default();
+        db.write_subspace_val(BlockHeight(1), &key("abc/x"), b"1")
+            .unwrap();
+        db.write_subspace_val(BlockHeight(1), &key("abcd"), b"2")
+            .unwrap();
+        db.write_subspace_val(BlockHeight(1), &key("abc_def"), b"3")
+            .unwrap();
+
+        db.delete_subspace_prefix(BlockHeight(2), &key("abc"))
+            .unwrap();
+
+        assert_eq!(db.read_subspace_val(&key("abc/x")).unwrap(), None);
+        assert_eq!(
+            db.read_subspace_val(&key("abcd")).unwrap(),
+            Some(b"2".to_vec())
+        );
+        assert_eq!(
+            db.read_subspace_val(&key("abc_def")).unwrap(),
+            Some(b"3".to_vec())
+        );
+    }
+
+    /// chunk0-4: `iter_prefix` must only yield keys in the requested
+    /// prefix's subtree, not lexicographic neighbors (see `subspace_prefix`).
+    #[test]
+    fn test_iter_prefix_respects_segment_boundary() {
+        let mut db = MockDB::default();
+        db.write_subspace_val(BlockHeight(1), &key("abc/x"), b"1")
+            .unwrap();
+        db.write_subspace_val(BlockHeight(1), &key("abcd"), b"2")
+            .unwrap();
+        db.write_subspace_val(BlockHeight(1), &key("abc_def"), b"3")
+            .unwrap();
+
+        let matched: Vec<String> = db
+            .iter_prefix(&key("abc"))
+            .map(|(key, _value, _gas)| key)
+            .collect();
+
+        assert_eq!(matched, vec!["abc/x".to_owned()]);
+    }
+}